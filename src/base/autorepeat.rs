@@ -0,0 +1,89 @@
+//  maschine.rs: user-space drivers for native instruments USB HIDs
+//  Copyright (C) 2015 William Light <wrl@illest.net>
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as
+//  published by the Free Software Foundation, either version 3 of the
+//  License, or (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public
+//  License along with this program.  If not, see
+//  <http://www.gnu.org/licenses/>.
+
+
+// ~400ms before repeating, then every ~80ms, at the event loop's 16ms tick.
+const DEFAULT_DELAY_TICKS: usize = 25;
+const DEFAULT_INTERVAL_TICKS: usize = 5;
+
+// keyboard-style autorepeat for whatever is currently held, timed in clock
+// ticks so that repeats stay in step with the rest of the tick-driven bits.
+pub struct Autorepeat<T> {
+    delay: usize,
+    interval: usize,
+
+    held: Option<(T, usize)>,
+    repeats: usize
+}
+
+impl<T> Default for Autorepeat<T> {
+    fn default() -> Self {
+        Autorepeat {
+            delay: DEFAULT_DELAY_TICKS,
+            interval: DEFAULT_INTERVAL_TICKS,
+
+            held: None,
+            repeats: 0
+        }
+    }
+}
+
+impl<T: Copy + PartialEq> Autorepeat<T> {
+    // an interval of 0 turns repeating off.
+    pub fn set(&mut self, delay: usize, interval: usize) {
+        self.delay = delay;
+        self.interval = interval;
+    }
+
+    pub fn press(&mut self, val: T, now: usize) {
+        self.held = Some((val, now));
+        self.repeats = 0;
+    }
+
+    pub fn clear(&mut self) {
+        self.held = None;
+    }
+
+    pub fn release(&mut self, val: T) {
+        if let Some((held, _)) = self.held {
+            if held == val {
+                self.held = None;
+            }
+        }
+    }
+
+    // returns the held value when a repeat is due.
+    pub fn poll(&mut self, now: usize) -> Option<T> {
+        let (val, pressed_at) = match self.held {
+            Some(held) => held,
+            None => return None
+        };
+
+        let elapsed = now.wrapping_sub(pressed_at);
+
+        if self.interval == 0 || elapsed < self.delay {
+            return None;
+        }
+
+        if 1 + ((elapsed - self.delay) / self.interval) > self.repeats {
+            self.repeats += 1;
+            Some(val)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,45 @@
+//  maschine.rs: user-space drivers for native instruments USB HIDs
+//  Copyright (C) 2015 William Light <wrl@illest.net>
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as
+//  published by the Free Software Foundation, either version 3 of the
+//  License, or (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public
+//  License along with this program.  If not, see
+//  <http://www.gnu.org/licenses/>.
+
+
+use std::sync::Arc;
+use std::sync::atomic::{
+    AtomicUsize,
+    Ordering
+};
+
+// a tick counter which can be handed to several devices (on several threads,
+// if need be) so that anything they drive off it stays in phase. whichever
+// loop owns the timer calls `tick()`; everybody else only reads it.
+#[derive(Clone,Default)]
+pub struct SharedClock {
+    ticks: Arc<AtomicUsize>
+}
+
+impl SharedClock {
+    pub fn new() -> Self {
+        SharedClock::default()
+    }
+
+    pub fn tick(&self) {
+        self.ticks.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub fn now(&self) -> usize {
+        self.ticks.load(Ordering::SeqCst)
+    }
+}
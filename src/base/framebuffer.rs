@@ -0,0 +1,154 @@
+//  maschine.rs: user-space drivers for native instruments USB HIDs
+//  Copyright (C) 2015 William Light <wrl@illest.net>
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as
+//  published by the Free Software Foundation, either version 3 of the
+//  License, or (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public
+//  License along with this program.  If not, see
+//  <http://www.gnu.org/licenses/>.
+
+
+#[derive(Copy,Clone,Debug)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize
+}
+
+// monochrome framebuffer laid out the way the mk2 displays want it: rows of
+// 8-pixel-high pages, one byte per column per page, LSB at the top.
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    data: Vec<u8>
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        Framebuffer {
+            width: width,
+            height: height,
+            data: vec![0u8; width * ((height + 7) / 8)]
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn clear(&mut self) {
+        for byte in self.data.iter_mut() {
+            *byte = 0;
+        }
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, on: bool) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+
+        let byte = &mut self.data[((y / 8) * self.width) + x];
+
+        if on {
+            *byte |= 1 << (y % 8);
+        } else {
+            *byte &= !(1 << (y % 8));
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn get_pixel(&self, x: usize, y: usize) -> bool {
+        if x >= self.width || y >= self.height {
+            return false;
+        }
+
+        (self.data[((y / 8) * self.width) + x] & (1 << (y % 8))) != 0
+    }
+
+    pub fn fill_rect(&mut self, rect: Rect, on: bool) {
+        for y in rect.y .. (rect.y + rect.height) {
+            for x in rect.x .. (rect.x + rect.width) {
+                self.set_pixel(x, y, on);
+            }
+        }
+    }
+
+    // maps x across the rect to [0, 1], feeds it through `f`, and plots the
+    // result with 0 at the bottom of the rect and 1 at the top. steep
+    // sections are joined up vertically so the line stays continuous.
+    pub fn plot<F: Fn(f32) -> f32>(&mut self, rect: Rect, f: F) {
+        if rect.width == 0 || rect.height == 0 {
+            return;
+        }
+
+        let mut prev_row = None;
+
+        for col in 0 .. rect.width {
+            let input = match rect.width {
+                1 => 0.0,
+                w => (col as f32) / ((w - 1) as f32)
+            };
+
+            let row = self.rect_row(rect, f(input));
+
+            let (from, to) = match prev_row {
+                Some(prev) if row + 1 < prev => (row, prev - 1),
+                Some(prev) if row > prev + 1 => (prev + 1, row),
+                _ => (row, row)
+            };
+
+            for r in from ..= to {
+                self.set_pixel(rect.x + col, rect.y + r, true);
+            }
+
+            prev_row = Some(row);
+        }
+    }
+
+    // small cross centred on the point (input, output) within the rect.
+    pub fn mark(&mut self, rect: Rect, input: f32, output: f32) {
+        if rect.width == 0 || rect.height == 0 {
+            return;
+        }
+
+        let input = input.clamp(0.0, 1.0);
+        let x = rect.x + ((input * ((rect.width - 1) as f32)).round() as usize);
+        let y = rect.y + self.rect_row(rect, output);
+
+        self.set_pixel(x, y, true);
+
+        if x > rect.x {
+            self.set_pixel(x - 1, y, true);
+        }
+
+        if x + 1 < rect.x + rect.width {
+            self.set_pixel(x + 1, y, true);
+        }
+
+        if y > rect.y {
+            self.set_pixel(x, y - 1, true);
+        }
+
+        if y + 1 < rect.y + rect.height {
+            self.set_pixel(x, y + 1, true);
+        }
+    }
+
+    fn rect_row(&self, rect: Rect, val: f32) -> usize {
+        let val = val.clamp(0.0, 1.0);
+        (rect.height - 1) - ((val * ((rect.height - 1) as f32)).round() as usize)
+    }
+}
@@ -16,6 +16,16 @@
 //  <http://www.gnu.org/licenses/>.
 
 use std::os::unix::io::RawFd;
+use std::time::{
+    Duration,
+    Instant
+};
+
+use base::{
+    PressureShape,
+    Rect,
+    SharedClock
+};
 
 #[derive(Copy,Clone,Debug)]
 pub enum MaschineButton {
@@ -54,6 +64,37 @@ pub enum MaschineButton {
     Mute
 }
 
+// up and down are there for devices with the buttons for them.
+#[allow(dead_code)]
+#[derive(Copy,Clone,Debug,PartialEq)]
+pub enum Direction {
+    Up,
+    Down,
+    Left,
+    Right
+}
+
+#[allow(dead_code)]
+#[derive(Copy,Clone,Debug)]
+pub struct PadStrike {
+    pub pad_idx: usize,
+    pub velocity: f32,
+    pub timestamp: Instant
+}
+
+#[derive(Copy,Clone,Debug)]
+pub struct SelfTestReport {
+    pub lights_written: bool,
+    pub screen_cleared: bool,
+    pub reports_read: usize
+}
+
+impl SelfTestReport {
+    pub fn passed(&self) -> bool {
+        self.lights_written && self.screen_cleared && self.reports_read > 0
+    }
+}
+
 pub trait Maschine {
     fn get_fd(&self) -> RawFd;
 
@@ -67,8 +108,77 @@ pub trait Maschine {
 
     fn readable(&mut self, &mut dyn MaschineHandler);
 
+    // integration point for local sample playback. the hook is called
+    // synchronously from the read loop right before `pad_pressed`, so it
+    // should hand the strike off (to an audio thread, say) and return.
+    #[allow(dead_code)]
+    fn set_audio_hook(&mut self, hook: Box<dyn Fn(PadStrike)>);
+
+    // per-pad gains, learned from strikes while enabled and not frozen, which
+    // even out pads that read hotter or colder than the rest. the gains can
+    // be read back and restored to carry them over between sessions.
+    #[allow(dead_code)]
+    fn set_auto_velocity_normalize(&mut self, enabled: bool);
+    #[allow(dead_code)]
+    fn freeze_velocity_normalize(&mut self, frozen: bool);
+    #[allow(dead_code)]
+    fn get_velocity_gains(&self) -> [f32; 16];
+    #[allow(dead_code)]
+    fn set_velocity_gains(&mut self, gains: [f32; 16]);
+
+    // strike velocities above `threshold` are scaled down by `ratio` past
+    // that point. applied after the per-pad gains.
+    #[allow(dead_code)]
+    fn set_velocity_compression(&mut self, threshold: f32, ratio: f32);
+
+    // while enabled, pressure on a held pad is also reported through
+    // `MaschineHandler::pad_position`, scaled into [min, max] and optionally
+    // inverted so that harder presses move towards `min`.
+    fn set_position_mode(&mut self, enabled: bool);
+    #[allow(dead_code)]
+    fn set_position_range(&mut self, min: f32, max: f32, invert: bool);
+
+    // pressing all four corner pads at once blanks the lights and calls
+    // `MaschineHandler::reset_gesture`. `None` disables the gesture,
+    // otherwise it's the longest the four presses may be spread over.
+    #[allow(dead_code)]
+    fn set_reset_gesture_window(&mut self, window: Option<Duration>);
+
     fn clear_screen(&mut self);
+
+    // briefly lights every LED, clears the screen and checks that reports
+    // are coming in. the lights are put back as they were afterwards.
+    fn self_test(&mut self) -> SelfTestReport;
+
+    // plots `curve` into `rect` on the screen. with `show_last_hit`, the last
+    // pad hit is marked on the curve as well; the mark doesn't follow later
+    // hits by itself, so call this again after a strike to move it.
+    #[allow(dead_code)]
+    fn draw_velocity_curve(&mut self, rect: Rect, curve: &PressureShape, show_last_hit: bool);
+
     fn write_lights(&mut self);
+
+    // devices sharing a clock keep their tick-driven lights (such as the
+    // metronome) in phase. lights follow the clock on each `write_lights`.
+    fn set_clock(&mut self, clock: SharedClock);
+
+    // flashes the play button once every `ticks_per_beat` clock ticks. the
+    // light is only written as each flash starts and ends, so it can still be
+    // set by other means in between.
+    #[allow(dead_code)]
+    fn set_metronome(&mut self, ticks_per_beat: Option<usize>);
+
+    // called from the event loop's timer, after the clock has ticked.
+    fn tick(&mut self, &mut dyn MaschineHandler);
+
+    // the nav buttons double as a d-pad, reported through
+    // `MaschineHandler::direction` and repeated while held.
+    #[allow(dead_code)]
+    fn set_direction_pad(&mut self, enabled: bool);
+
+    // in clock ticks. an interval of 0 disables repeating.
+    #[allow(dead_code)]
+    fn set_autorepeat(&mut self, delay: usize, interval: usize);
 }
 
 #[allow(unused_variables)]
@@ -76,9 +186,14 @@ pub trait MaschineHandler {
     fn pad_pressed(&mut self, &mut dyn Maschine, pad_idx: usize, pressure: f32) {}
     fn pad_aftertouch(&mut self, &mut dyn Maschine, pad_idx: usize, pressure: f32) {}
     fn pad_released(&mut self, &mut dyn Maschine, pad_idx: usize) {}
+    fn pad_position(&mut self, &mut dyn Maschine, pad_idx: usize, position: f32) {}
 
     fn encoder_step(&mut self, &mut dyn Maschine, encoder_idx: usize, delta: i32) {}
 
     fn button_down(&mut self, &mut dyn Maschine, button: MaschineButton) {}
     fn button_up(&mut self, &mut dyn Maschine, button: MaschineButton) {}
+
+    fn direction(&mut self, &mut dyn Maschine, direction: Direction) {}
+
+    fn reset_gesture(&mut self, &mut dyn Maschine) {}
 }
@@ -19,7 +19,10 @@ pub mod maschine;
 pub use self::maschine::{
     Maschine,
     MaschineHandler,
-    MaschineButton
+    MaschineButton,
+    Direction,
+    PadStrike,
+    SelfTestReport
 };
 
 pub mod maschine_pad;
@@ -27,3 +30,33 @@ pub use self::maschine_pad::{
     MaschinePad,
     MaschinePadStateTransition
 };
+
+pub mod pressure_shape;
+pub use self::pressure_shape::PressureShape;
+
+pub mod framebuffer;
+pub use self::framebuffer::{
+    Framebuffer,
+    Rect
+};
+
+pub mod velocity_normalizer;
+pub use self::velocity_normalizer::VelocityNormalizer;
+
+pub mod velocity_compressor;
+pub use self::velocity_compressor::VelocityCompressor;
+
+pub mod position_mapping;
+pub use self::position_mapping::PositionMapping;
+
+pub mod reset_gesture;
+pub use self::reset_gesture::{
+    ResetGesture,
+    DEFAULT_WINDOW_MS as DEFAULT_RESET_WINDOW_MS
+};
+
+pub mod clock;
+pub use self::clock::SharedClock;
+
+pub mod autorepeat;
+pub use self::autorepeat::Autorepeat;
@@ -0,0 +1,64 @@
+//  maschine.rs: user-space drivers for native instruments USB HIDs
+//  Copyright (C) 2015 William Light <wrl@illest.net>
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as
+//  published by the Free Software Foundation, either version 3 of the
+//  License, or (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public
+//  License along with this program.  If not, see
+//  <http://www.gnu.org/licenses/>.
+
+
+// reinterprets held pad pressure as a position within [min, max], for
+// scrubbing through a sample and the like.
+pub struct PositionMapping {
+    enabled: bool,
+
+    min: f32,
+    max: f32,
+    invert: bool
+}
+
+impl Default for PositionMapping {
+    fn default() -> Self {
+        PositionMapping {
+            enabled: false,
+
+            min: 0.0,
+            max: 1.0,
+            invert: false
+        }
+    }
+}
+
+impl PositionMapping {
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn set_range(&mut self, min: f32, max: f32, invert: bool) {
+        self.min = min;
+        self.max = max;
+        self.invert = invert;
+    }
+
+    pub fn position(&self, pressure: f32) -> Option<f32> {
+        if !self.enabled {
+            return None;
+        }
+
+        let pressure = match pressure.clamp(0.0, 1.0) {
+            pressure if self.invert => 1.0 - pressure,
+            pressure => pressure
+        };
+
+        Some(self.min + ((self.max - self.min) * pressure))
+    }
+}
@@ -0,0 +1,66 @@
+//  maschine.rs: user-space drivers for native instruments USB HIDs
+//  Copyright (C) 2015 William Light <wrl@illest.net>
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as
+//  published by the Free Software Foundation, either version 3 of the
+//  License, or (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public
+//  License along with this program.  If not, see
+//  <http://www.gnu.org/licenses/>.
+
+
+#[allow(dead_code)]
+pub enum PressureShape {
+    Linear,
+    Exponential(f32),
+    Constant(f32),
+
+    // (pressure, output) pairs sorted by pressure, linearly interpolated.
+    // pressures outside the first/last breakpoint clamp to their outputs.
+    Breakpoints(Vec<(f32, f32)>)
+}
+
+impl PressureShape {
+    pub fn apply(&self, pressure: f32) -> f32 {
+        match *self {
+            PressureShape::Linear => pressure,
+            PressureShape::Exponential(power) => pressure.powf(power),
+            PressureShape::Constant(c_pressure) => c_pressure,
+            PressureShape::Breakpoints(ref points) => interpolate(points, pressure)
+        }
+    }
+}
+
+fn interpolate(points: &[(f32, f32)], pressure: f32) -> f32 {
+    let (first, last) = match (points.first(), points.last()) {
+        (Some(&first), Some(&last)) => (first, last),
+        _ => return pressure
+    };
+
+    if pressure <= first.0 {
+        return first.1;
+    } else if pressure >= last.0 {
+        return last.1;
+    }
+
+    for pair in points.windows(2) {
+        let ((x0, y0), (x1, y1)) = (pair[0], pair[1]);
+
+        if pressure <= x1 {
+            if x1 <= x0 {
+                return y1;
+            }
+
+            return y0 + ((pressure - x0) / (x1 - x0)) * (y1 - y0);
+        }
+    }
+
+    last.1
+}
@@ -0,0 +1,88 @@
+//  maschine.rs: user-space drivers for native instruments USB HIDs
+//  Copyright (C) 2015 William Light <wrl@illest.net>
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as
+//  published by the Free Software Foundation, either version 3 of the
+//  License, or (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public
+//  License along with this program.  If not, see
+//  <http://www.gnu.org/licenses/>.
+
+
+use std::time::{
+    Duration,
+    Instant
+};
+
+const CORNER_PADS: [usize; 4] = [0, 3, 12, 15];
+
+// all four corners have to land within this long of each other.
+pub const DEFAULT_WINDOW_MS: u64 = 200;
+
+pub struct ResetGesture {
+    window: Option<Duration>,
+    pressed_at: [Option<Instant>; 4]
+}
+
+impl Default for ResetGesture {
+    fn default() -> Self {
+        ResetGesture {
+            window: Some(Duration::from_millis(DEFAULT_WINDOW_MS)),
+            pressed_at: [None; 4]
+        }
+    }
+}
+
+impl ResetGesture {
+    // `None` disables the gesture.
+    pub fn set_window(&mut self, window: Option<Duration>) {
+        self.window = window;
+        self.pressed_at = [None; 4];
+    }
+
+    // returns true when this press completes the gesture.
+    pub fn pad_pressed(&mut self, pad_idx: usize, now: Instant) -> bool {
+        let window = match self.window {
+            Some(window) => window,
+            None => return false
+        };
+
+        let corner = match CORNER_PADS.iter().position(|&pad| pad == pad_idx) {
+            Some(corner) => corner,
+            None => return false
+        };
+
+        self.pressed_at[corner] = Some(now);
+
+        let mut earliest = now;
+
+        for pressed_at in self.pressed_at.iter() {
+            match *pressed_at {
+                Some(at) if at < earliest => earliest = at,
+                Some(_) => {},
+                None => return false
+            }
+        }
+
+        if now.duration_since(earliest) > window {
+            return false;
+        }
+
+        // only fire once per gesture, not again for as long as it's held
+        self.pressed_at = [None; 4];
+        true
+    }
+
+    pub fn pad_released(&mut self, pad_idx: usize) {
+        if let Some(corner) = CORNER_PADS.iter().position(|&pad| pad == pad_idx) {
+            self.pressed_at[corner] = None;
+        }
+    }
+}
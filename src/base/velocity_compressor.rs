@@ -0,0 +1,48 @@
+//  maschine.rs: user-space drivers for native instruments USB HIDs
+//  Copyright (C) 2015 William Light <wrl@illest.net>
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as
+//  published by the Free Software Foundation, either version 3 of the
+//  License, or (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public
+//  License along with this program.  If not, see
+//  <http://www.gnu.org/licenses/>.
+
+
+// hard-knee compressor: velocities above the threshold have the part above
+// it divided by the ratio. the defaults pass everything through untouched.
+pub struct VelocityCompressor {
+    threshold: f32,
+    ratio: f32
+}
+
+impl Default for VelocityCompressor {
+    fn default() -> Self {
+        VelocityCompressor {
+            threshold: 1.0,
+            ratio: 1.0
+        }
+    }
+}
+
+impl VelocityCompressor {
+    pub fn set(&mut self, threshold: f32, ratio: f32) {
+        self.threshold = threshold.clamp(0.0, 1.0);
+        self.ratio = ratio.max(1.0);
+    }
+
+    pub fn apply(&self, velocity: f32) -> f32 {
+        if velocity > self.threshold {
+            self.threshold + ((velocity - self.threshold) / self.ratio)
+        } else {
+            velocity
+        }
+    }
+}
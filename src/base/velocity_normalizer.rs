@@ -0,0 +1,97 @@
+//  maschine.rs: user-space drivers for native instruments USB HIDs
+//  Copyright (C) 2015 William Light <wrl@illest.net>
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as
+//  published by the Free Software Foundation, either version 3 of the
+//  License, or (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public
+//  License along with this program.  If not, see
+//  <http://www.gnu.org/licenses/>.
+
+
+// how quickly the per-pad averages and gains follow new strikes.
+const LEARNING_RATE: f32 = 0.2;
+
+const MIN_GAIN: f32 = 0.25;
+const MAX_GAIN: f32 = 4.0;
+
+// learns, per pad, a gain which brings that pad's average strike velocity in
+// line with the average across all pads that have been hit so far.
+pub struct VelocityNormalizer {
+    enabled: bool,
+    frozen: bool,
+
+    averages: [Option<f32>; 16],
+    gains: [f32; 16]
+}
+
+impl Default for VelocityNormalizer {
+    fn default() -> Self {
+        VelocityNormalizer {
+            enabled: false,
+            frozen: false,
+
+            averages: [None; 16],
+            gains: [1.0; 16]
+        }
+    }
+}
+
+impl VelocityNormalizer {
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn set_frozen(&mut self, frozen: bool) {
+        self.frozen = frozen;
+    }
+
+    pub fn gains(&self) -> [f32; 16] {
+        self.gains
+    }
+
+    pub fn set_gains(&mut self, gains: [f32; 16]) {
+        self.gains = gains;
+    }
+
+    pub fn strike(&mut self, pad_idx: usize, velocity: f32) -> f32 {
+        if !self.enabled {
+            return velocity;
+        }
+
+        if !self.frozen {
+            self.learn(pad_idx, velocity);
+        }
+
+        (velocity * self.gains[pad_idx]).min(1.0)
+    }
+
+    fn learn(&mut self, pad_idx: usize, velocity: f32) {
+        self.averages[pad_idx] = Some(match self.averages[pad_idx] {
+            Some(avg) => avg + LEARNING_RATE * (velocity - avg),
+            None => velocity
+        });
+
+        let (sum, count) = self.averages.iter()
+            .filter_map(|&avg| avg)
+            .fold((0.0, 0), |(sum, count), avg| (sum + avg, count + 1));
+
+        let reference = sum / (count as f32);
+
+        for (gain, avg) in self.gains.iter_mut().zip(self.averages.iter()) {
+            if let Some(avg) = *avg {
+                if avg > 0.0 {
+                    let target = (reference / avg).clamp(MIN_GAIN, MAX_GAIN);
+                    *gain += LEARNING_RATE * (target - *gain);
+                }
+            }
+        }
+    }
+}
@@ -16,20 +16,45 @@
 //  <http://www.gnu.org/licenses/>.
 
 use std::mem::transmute;
+use std::thread;
 use std::os::unix::io;
+use std::time::{
+    Duration,
+    Instant
+};
 
 extern crate nix;
 use nix::unistd;
+use nix::poll::*;
 
 use base::{
     Maschine,
     MaschineHandler,
     MaschineButton,
+    Direction,
+    PadStrike,
+    SelfTestReport,
 
     MaschinePad,
-    MaschinePadStateTransition
+    MaschinePadStateTransition,
+
+    PressureShape,
+    Framebuffer,
+    Rect,
+
+    VelocityNormalizer,
+    VelocityCompressor,
+    PositionMapping,
+    ResetGesture,
+
+    SharedClock,
+    Autorepeat
 };
 
+// long enough for the lights to be seen. the device streams reports
+// constantly, so plenty should turn up while they're on.
+const SELF_TEST_LIT_MS: u64 = 300;
+
 const BUTTON_REPORT_TO_MIKROBUTTONS_MAP: [[Option<MaschineButton>; 8]; 4] = [
     [
         Some(MaschineButton::Restart),
@@ -89,7 +114,24 @@ pub struct Mikro {
     pads: [MaschinePad; 16],
     buttons: [u8; 5],
 
-    midi_note_base: u8
+    midi_note_base: u8,
+    normalizer: VelocityNormalizer,
+    compressor: VelocityCompressor,
+    position: PositionMapping,
+    last_position: [Option<f32>; 16],
+    reset_gesture: ResetGesture,
+
+    screen: Framebuffer,
+    last_strike: Option<f32>,
+
+    audio_hook: Option<Box<dyn Fn(PadStrike)>>,
+
+    clock: SharedClock,
+    metronome: Option<usize>,
+    metronome_lit: Option<bool>,
+
+    direction_pad: bool,
+    autorepeat: Autorepeat<Direction>
 }
 
 impl Mikro {
@@ -122,7 +164,24 @@ impl Mikro {
             pads: Mikro::sixteen_maschine_pads(),
             buttons: [0, 0, 0, 0, 0x10],
 
-            midi_note_base: 48
+            midi_note_base: 48,
+            normalizer: VelocityNormalizer::default(),
+            compressor: VelocityCompressor::default(),
+            position: PositionMapping::default(),
+            last_position: [None; 16],
+            reset_gesture: ResetGesture::default(),
+
+            screen: Framebuffer::new(128, 64),
+            last_strike: None,
+
+            audio_hook: None,
+
+            clock: SharedClock::new(),
+            metronome: None,
+            metronome_lit: None,
+
+            direction_pad: true,
+            autorepeat: Autorepeat::default()
         };
 
         _self.light_buf[0] = 0x80;
@@ -141,8 +200,10 @@ impl Mikro {
 
                 if (byte & (1 << (off - 1))) != 0 {
                     handler.button_down(self, btn);
+                    self.direction_down(handler, btn);
                 } else {
                     handler.button_up(self, btn);
+                    self.direction_up(btn);
                 }
 
                 diff >>= off;
@@ -167,6 +228,121 @@ impl Mikro {
         self.buttons[4] = buf[4];
     }
 
+    fn direction_down(&mut self, handler: &mut dyn MaschineHandler, btn: MaschineButton) {
+        if !self.direction_pad {
+            return;
+        }
+
+        if let Some(direction) = button_to_direction(btn) {
+            self.autorepeat.press(direction, self.clock.now());
+            handler.direction(self, direction);
+        }
+    }
+
+    fn direction_up(&mut self, btn: MaschineButton) {
+        if let Some(direction) = button_to_direction(btn) {
+            self.autorepeat.release(direction);
+        }
+    }
+
+    fn update_metronome(&mut self) {
+        let ticks_per_beat = match self.metronome {
+            Some(ticks_per_beat) => ticks_per_beat,
+            None => return
+        };
+
+        let flash_ticks = (ticks_per_beat / 4).max(1);
+        let lit = (self.clock.now() % ticks_per_beat) < flash_ticks;
+
+        // only on the flash's edges, so the play light can still be set
+        // from elsewhere in between.
+        if self.metronome_lit == Some(lit) {
+            return;
+        }
+
+        self.metronome_lit = Some(lit);
+        self.set_button_light(MaschineButton::Play, 0xFFFFFF, if lit { 1.0 } else { 0.0 });
+    }
+
+    fn blank_lights(&mut self) {
+        for byte in self.light_buf[1 ..].iter_mut() {
+            *byte = 0;
+        }
+    }
+
+    fn write_screen(&mut self) -> nix::Result<()> {
+        let mut screen_buf = [0u8; 1 + 8 + 256];
+
+        screen_buf[0] = 0xE0;
+
+        screen_buf[5] = 0x20;
+        screen_buf[7] = 0x08;
+
+        let width = self.screen.width();
+
+        for i in 0..4 {
+            screen_buf[1] = (i * 32) as u8;
+
+            for page in 0..8 {
+                let offset = (page * width) + (i * 32);
+                screen_buf[(9 + page * 32) .. (9 + (page + 1) * 32)]
+                    .copy_from_slice(&self.screen.data()[offset .. (offset + 32)]);
+            }
+
+            unistd::write(self.dev, &screen_buf)?;
+        }
+
+        Ok(())
+    }
+
+    // reads (and drops) up to `count` reports, giving up after a timeout.
+    // reads (and drops) whatever reports arrive for `duration`, returning
+    // how many there were. it takes the full `duration` even if the device
+    // stops answering.
+    fn read_reports(&mut self, duration: Duration) -> usize {
+        let mut fds = [PollFd::new(self.dev, POLLIN, EventFlags::empty())];
+        let mut buf = [0u8; 256];
+
+        let deadline = Instant::now() + duration;
+        let mut read = 0;
+
+        while Instant::now() < deadline {
+            let remaining = deadline - Instant::now();
+            let timeout = (remaining.as_secs() as i32) * 1000
+                + remaining.subsec_millis() as i32 + 1;
+
+            match poll(&mut fds, timeout) {
+                Ok(ready) if ready > 0 => {},
+                Ok(_) => continue,
+                Err(_) => break
+            }
+
+            match unistd::read(self.dev, &mut buf) {
+                Ok(nbytes) if nbytes > 0 => read += 1,
+                _ => break
+            }
+        }
+
+        let now = Instant::now();
+        if now < deadline {
+            thread::sleep(deadline - now);
+        }
+
+        read
+    }
+
+    // only sends the position when it has moved since it was last sent.
+    fn update_position(&mut self, handler: &mut dyn MaschineHandler, pad_idx: usize) {
+        let filtered = self.pads[pad_idx].get_pressure();
+
+        if let Some(position) = self.position.position(filtered) {
+            if self.last_position[pad_idx] != Some(position) {
+                self.last_position[pad_idx] = Some(position);
+                handler.pad_position(self, pad_idx, position);
+            }
+        }
+    }
+
     fn read_pads(&mut self, handler: &mut dyn MaschineHandler, buf: &[u8]) {
         let pads: &[u16] = unsafe { transmute(buf) };
 
@@ -174,14 +350,40 @@ impl Mikro {
             let pressure = ((pads[i] & 0xFFF) as f32) / 4095.0;
 
             match self.pads[i].pressure_val(pressure) {
-                MaschinePadStateTransition::Pressed =>
-                    handler.pad_pressed(self, i, pressure),
+                MaschinePadStateTransition::Pressed => {
+                    let velocity = self.compressor.apply(
+                        self.normalizer.strike(i, pressure));
+
+                    if let Some(ref hook) = self.audio_hook {
+                        hook(PadStrike {
+                            pad_idx: i,
+                            velocity: velocity,
+                            timestamp: Instant::now()
+                        });
+                    }
+
+                    self.last_strike = Some(velocity);
+                    handler.pad_pressed(self, i, velocity);
+
+                    self.update_position(handler, i);
+
+                    if self.reset_gesture.pad_pressed(i, Instant::now()) {
+                        self.blank_lights();
+                        handler.reset_gesture(self);
+                    }
+                },
 
-                MaschinePadStateTransition::Aftertouch =>
-                    handler.pad_aftertouch(self, i, pressure),
+                MaschinePadStateTransition::Aftertouch => {
+                    handler.pad_aftertouch(self, i, pressure);
 
-                MaschinePadStateTransition::Released =>
-                    handler.pad_released(self, i),
+                    self.update_position(handler, i);
+                },
+
+                MaschinePadStateTransition::Released => {
+                    self.last_position[i] = None;
+                    self.reset_gesture.pad_released(i);
+                    handler.pad_released(self, i)
+                },
 
                 _ => {}
             }
@@ -189,6 +391,14 @@ impl Mikro {
     }
 }
 
+fn button_to_direction(btn: MaschineButton) -> Option<Direction> {
+    match btn {
+        MaschineButton::NavLeft => Some(Direction::Left),
+        MaschineButton::NavRight => Some(Direction::Right),
+        _ => None
+    }
+}
+
 fn set_rgb_light(rgb: &mut [u8], color: u32, brightness: f32) {
     let brightness = brightness * 0.5;
 
@@ -203,9 +413,47 @@ impl Maschine for Mikro {
     }
 
     fn write_lights(&mut self) {
+        self.update_metronome();
         unistd::write(self.dev, &self.light_buf).unwrap();
     }
 
+    fn set_clock(&mut self, clock: SharedClock) {
+        self.clock = clock;
+    }
+
+    fn set_metronome(&mut self, ticks_per_beat: Option<usize>) {
+        let ticks_per_beat = ticks_per_beat.filter(|&ticks| ticks > 0);
+
+        if ticks_per_beat.is_none() && self.metronome.is_some() {
+            self.set_button_light(MaschineButton::Play, 0xFFFFFF, 0.0);
+        }
+
+        self.metronome = ticks_per_beat;
+        self.metronome_lit = None;
+    }
+
+    fn tick(&mut self, handler: &mut dyn MaschineHandler) {
+        if !self.direction_pad {
+            return;
+        }
+
+        if let Some(direction) = self.autorepeat.poll(self.clock.now()) {
+            handler.direction(self, direction);
+        }
+    }
+
+    fn set_direction_pad(&mut self, enabled: bool) {
+        if !enabled {
+            self.autorepeat.clear();
+        }
+
+        self.direction_pad = enabled;
+    }
+
+    fn set_autorepeat(&mut self, delay: usize, interval: usize) {
+        self.autorepeat.set(delay, interval);
+    }
+
     fn set_pad_light(&mut self, pad: usize, color: u32, brightness: f32) {
         let offset = 31 + (pad * 3);
         let rgb = &mut self.light_buf[offset .. (offset + 3)];
@@ -289,6 +537,42 @@ impl Maschine for Mikro {
         }
     }
 
+    fn set_audio_hook(&mut self, hook: Box<dyn Fn(PadStrike)>) {
+        self.audio_hook = Some(hook);
+    }
+
+    fn set_auto_velocity_normalize(&mut self, enabled: bool) {
+        self.normalizer.set_enabled(enabled);
+    }
+
+    fn freeze_velocity_normalize(&mut self, frozen: bool) {
+        self.normalizer.set_frozen(frozen);
+    }
+
+    fn get_velocity_gains(&self) -> [f32; 16] {
+        self.normalizer.gains()
+    }
+
+    fn set_velocity_gains(&mut self, gains: [f32; 16]) {
+        self.normalizer.set_gains(gains);
+    }
+
+    fn set_velocity_compression(&mut self, threshold: f32, ratio: f32) {
+        self.compressor.set(threshold, ratio);
+    }
+
+    fn set_position_mode(&mut self, enabled: bool) {
+        self.position.set_enabled(enabled);
+    }
+
+    fn set_position_range(&mut self, min: f32, max: f32, invert: bool) {
+        self.position.set_range(min, max, invert);
+    }
+
+    fn set_reset_gesture_window(&mut self, window: Option<Duration>) {
+        self.reset_gesture.set_window(window);
+    }
+
     fn get_pad_pressure(&self, pad_idx: usize) -> Result<f32, ()> {
         match pad_idx {
             0 ..= 15 => Ok(self.pads[pad_idx].get_pressure()),
@@ -297,16 +581,53 @@ impl Maschine for Mikro {
     }
 
     fn clear_screen(&mut self) {
-        let mut screen_buf = [0u8; 1 + 8 + 256];
+        self.screen.clear();
+        self.write_screen().unwrap();
+    }
 
-        screen_buf[0] = 0xE0;
+    fn self_test(&mut self) -> SelfTestReport {
+        let saved_lights = self.light_buf;
 
-        screen_buf[5] = 0x20;
-        screen_buf[7] = 0x08;
+        // through the usual setters, so nothing is driven any harder than
+        // the driver would normally drive it.
+        for pad in 0..16 {
+            self.set_pad_light(pad, 0xFFFFFF, 1.0);
+        }
 
-        for i in 0..4 {
-            screen_buf[1] = i * 32;
-            unistd::write(self.dev, &screen_buf).unwrap();
+        for row in BUTTON_REPORT_TO_MIKROBUTTONS_MAP.iter() {
+            for btn in row.iter().filter_map(|&btn| btn) {
+                self.set_button_light(btn, 0xFFFFFF, 1.0);
+            }
+        }
+
+        let lights_lit = unistd::write(self.dev, &self.light_buf).is_ok();
+
+        self.screen.clear();
+        let screen_cleared = self.write_screen().is_ok();
+
+        // the lights stay on while the reports are read
+        let reports_read = self.read_reports(Duration::from_millis(SELF_TEST_LIT_MS));
+
+        self.light_buf = saved_lights;
+        let lights_restored = unistd::write(self.dev, &self.light_buf).is_ok();
+
+        SelfTestReport {
+            lights_written: lights_lit && lights_restored,
+            screen_cleared: screen_cleared,
+            reports_read: reports_read
+        }
+    }
+
+    fn draw_velocity_curve(&mut self, rect: Rect, curve: &PressureShape, show_last_hit: bool) {
+        self.screen.fill_rect(rect, false);
+        self.screen.plot(rect, |pressure| curve.apply(pressure));
+
+        if show_last_hit {
+            if let Some(pressure) = self.last_strike {
+                self.screen.mark(rect, pressure, curve.apply(pressure));
+            }
         }
+
+        self.write_screen().unwrap();
     }
 }
@@ -0,0 +1,104 @@
+//  maschine.rs: user-space drivers for native instruments USB HIDs
+//  Copyright (C) 2015 William Light <wrl@illest.net>
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as
+//  published by the Free Software Foundation, either version 3 of the
+//  License, or (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public
+//  License along with this program.  If not, see
+//  <http://www.gnu.org/licenses/>.
+
+
+use std::collections::VecDeque;
+
+use midi::U7;
+
+// how far each new pressure reading moves the output, to keep the CC from
+// jittering along with the sensor.
+const SMOOTHING: f32 = 0.3;
+
+// how many readings back a held value is taken from on release. the
+// fingertip lifting off drags the pressure down over the last few readings
+// before the pad reads as released, and the held value should be from
+// before that.
+const HOLD_LOOKBACK: usize = 16;
+
+// one pad's pressure as an expression pedal on a MIDI CC. with `hold` set,
+// the value stays where it was before the pad was let go, until it's
+// pressed again; otherwise it falls back to zero.
+pub struct ExpressionPedal {
+    pad_idx: usize,
+    cc: U7,
+    hold: bool,
+
+    value: f32,
+    history: VecDeque<f32>,
+    last_sent: Option<U7>
+}
+
+impl ExpressionPedal {
+    pub fn new(pad_idx: usize, cc: U7, hold: bool) -> Self {
+        ExpressionPedal {
+            pad_idx: pad_idx,
+            cc: cc & 0x7F,
+            hold: hold,
+
+            value: 0.0,
+            history: VecDeque::with_capacity(HOLD_LOOKBACK),
+            last_sent: None
+        }
+    }
+
+    pub fn pad_idx(&self) -> usize {
+        self.pad_idx
+    }
+
+    pub fn cc(&self) -> U7 {
+        self.cc
+    }
+
+    pub fn value(&self) -> U7 {
+        (self.value.clamp(0.0, 1.0) * 127.0).round() as U7
+    }
+
+    // each of these returns the new CC value when it has changed.
+    pub fn pressure(&mut self, pressure: f32) -> Option<U7> {
+        self.value += SMOOTHING * (pressure - self.value);
+
+        if self.history.len() == HOLD_LOOKBACK {
+            self.history.pop_front();
+        }
+
+        self.history.push_back(self.value);
+        self.changed()
+    }
+
+    pub fn released(&mut self) -> Option<U7> {
+        self.value = if self.hold {
+            self.history.front().cloned().unwrap_or(self.value)
+        } else {
+            0.0
+        };
+
+        self.history.clear();
+        self.changed()
+    }
+
+    fn changed(&mut self) -> Option<U7> {
+        let value = self.value();
+
+        if self.last_sent == Some(value) {
+            return None;
+        }
+
+        self.last_sent = Some(value);
+        Some(value)
+    }
+}
@@ -0,0 +1,228 @@
+//  maschine.rs: user-space drivers for native instruments USB HIDs
+//  Copyright (C) 2015 William Light <wrl@illest.net>
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as
+//  published by the Free Software Foundation, either version 3 of the
+//  License, or (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public
+//  License along with this program.  If not, see
+//  <http://www.gnu.org/licenses/>.
+
+
+use std::net::{
+    UdpSocket,
+    SocketAddr,
+    IpAddr,
+    Ipv4Addr
+};
+
+use tinyosc as osc;
+
+use base::{
+    Maschine,
+    MaschineHandler
+};
+
+const GRID_WIDTH: usize = 4;
+const GRID_HEIGHT: usize = 4;
+
+// presents the pads as a 4x4 monome grid speaking the serialosc grid
+// protocol: pad presses go out as `<prefix>/grid/key x y s`, and
+// `<prefix>/grid/led/*` messages coming in light the pads. (0, 0) is the
+// top left pad. apps pick where keys are sent and the prefix through the
+// usual `/sys/*` messages.
+pub struct MonomeGrid<'a> {
+    prefix: String,
+    color: u32,
+
+    osc_socket: &'a UdpSocket,
+    osc_outgoing_addr: SocketAddr
+}
+
+impl<'a> MonomeGrid<'a> {
+    pub fn new(osc_socket: &'a UdpSocket, osc_outgoing_addr: SocketAddr, prefix: &str) -> Self {
+        MonomeGrid {
+            prefix: prefix.to_string(),
+            color: 0xFFFFFF,
+
+            osc_socket: osc_socket,
+            osc_outgoing_addr: osc_outgoing_addr
+        }
+    }
+
+    fn send_msg(&self, addr: &SocketAddr, path: &str, arguments: Vec<osc::Argument>) {
+        let msg = osc::Message {
+            path: path,
+            arguments: arguments
+        };
+
+        match self.osc_socket.send_to(&*msg.serialize().unwrap(), addr) {
+            Ok(_) => {},
+            Err(e) => println!(" :: error in send_to: {}", e)
+        }
+    }
+
+    fn send_key(&self, pad_idx: usize, state: i32) {
+        self.send_msg(&self.osc_outgoing_addr, &*format!("{}/grid/key", self.prefix),
+                      osc_args![(pad_idx % GRID_WIDTH) as i32,
+                                (pad_idx / GRID_WIDTH) as i32,
+                                state]);
+    }
+
+    fn send_size(&self, addr: &SocketAddr) {
+        self.send_msg(addr, "/sys/size",
+                      osc_args![GRID_WIDTH as i32, GRID_HEIGHT as i32]);
+    }
+
+    fn send_info(&self, addr: &SocketAddr) {
+        let host = self.osc_outgoing_addr.ip().to_string();
+
+        self.send_msg(addr, "/sys/id", vec![osc::Argument::s("maschine.rs")]);
+        self.send_size(addr);
+        self.send_msg(addr, "/sys/host", vec![osc::Argument::s(&*host)]);
+        self.send_msg(addr, "/sys/port", osc_args![self.osc_outgoing_addr.port() as i32]);
+        self.send_msg(addr, "/sys/prefix", vec![osc::Argument::s(&*self.prefix)]);
+        self.send_msg(addr, "/sys/rotation", osc_args![0]);
+    }
+
+    fn handle_sys_msg(&mut self, msg: &osc::Message) -> bool {
+        let args = &msg.arguments;
+
+        match &msg.path[4 ..] {
+            "/port" => {
+                if let Some(&osc::Argument::i(port)) = args.get(0) {
+                    if let Some(port) = parse_port(port) {
+                        self.osc_outgoing_addr.set_port(port);
+                    }
+                }
+            },
+
+            "/host" => {
+                if let Some(&osc::Argument::s(host)) = args.get(0) {
+                    if let Some(ip) = parse_host(host) {
+                        self.osc_outgoing_addr.set_ip(ip);
+                    }
+                }
+            },
+
+            "/prefix" => {
+                if let Some(&osc::Argument::s(prefix)) = args.get(0) {
+                    self.prefix = prefix.to_string();
+                }
+            },
+
+            "/size" => self.send_size(&self.osc_outgoing_addr),
+
+            // replies go to the given host and port (or just the port on the
+            // current host), without changing where keys are sent.
+            "/info" => {
+                let mut addr = self.osc_outgoing_addr;
+
+                match (args.get(0), args.get(1)) {
+                    (Some(&osc::Argument::i(port)), None) =>
+                        match parse_port(port) {
+                            Some(port) => addr.set_port(port),
+                            None => return true
+                        },
+
+                    (Some(&osc::Argument::s(host)), Some(&osc::Argument::i(port))) => {
+                        if let Some(ip) = parse_host(host) {
+                            addr.set_ip(ip);
+                        }
+
+                        match parse_port(port) {
+                            Some(port) => addr.set_port(port),
+                            None => return true
+                        }
+                    },
+
+                    _ => {}
+                }
+
+                self.send_info(&addr);
+            },
+
+            _ => return false
+        }
+
+        true
+    }
+
+    fn set_led(&self, maschine: &mut dyn Maschine, x: i32, y: i32, state: i32) {
+        if x < 0 || y < 0 || x as usize >= GRID_WIDTH || y as usize >= GRID_HEIGHT {
+            return;
+        }
+
+        let brightness = match state {
+            0 => 0.0,
+            _ => 1.0
+        };
+
+        maschine.set_pad_light(((y as usize) * GRID_WIDTH) + (x as usize), self.color, brightness);
+    }
+
+    // returns false if the message isn't one of ours.
+    pub fn handle_osc_msg(&mut self, maschine: &mut dyn Maschine, msg: &osc::Message) -> bool {
+        if msg.path.starts_with("/sys/") {
+            return self.handle_sys_msg(msg);
+        }
+
+        if !msg.path.starts_with(&*self.prefix) {
+            return false;
+        }
+
+        match &msg.path[self.prefix.len() ..] {
+            "/grid/led/set" => {
+                if let (Some(&osc::Argument::i(x)), Some(&osc::Argument::i(y)), Some(&osc::Argument::i(s)))
+                    = (msg.arguments.get(0), msg.arguments.get(1), msg.arguments.get(2)) {
+                    self.set_led(maschine, x, y, s);
+                }
+            },
+
+            "/grid/led/all" => {
+                if let Some(&osc::Argument::i(s)) = msg.arguments.get(0) {
+                    for pad_idx in 0 .. (GRID_WIDTH * GRID_HEIGHT) {
+                        self.set_led(maschine, (pad_idx % GRID_WIDTH) as i32,
+                                     (pad_idx / GRID_WIDTH) as i32, s);
+                    }
+                }
+            },
+
+            _ => return false
+        }
+
+        true
+    }
+}
+
+fn parse_host(host: &str) -> Option<IpAddr> {
+    match host {
+        "localhost" => Some(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))),
+        host => host.parse().ok()
+    }
+}
+
+fn parse_port(port: i32) -> Option<u16> {
+    if port < 0 || port > 65535 {
+        return None;
+    }
+
+    Some(port as u16)
+}
+
+impl<'a> MaschineHandler for MonomeGrid<'a> {
+    fn pad_pressed(&mut self, _: &mut dyn Maschine, pad_idx: usize, _: f32) {
+        self.send_key(pad_idx, 1);
+    }
+
+    fn pad_released(&mut self, _: &mut dyn Maschine, pad_idx: usize) {
+        self.send_key(pad_idx, 0);
+    }
+}
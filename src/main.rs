@@ -50,14 +50,26 @@ use tinyosc as osc;
 
 mod devices;
 mod base;
+mod grid;
+mod expression;
+
+#[cfg(test)]
+mod test;
 
 use base::{
     Maschine,
     MaschineHandler,
-    MaschineButton
+    MaschineButton,
+
+    PressureShape,
+    SharedClock,
+    DEFAULT_RESET_WINDOW_MS
 };
 
-fn ev_loop(dev: &mut dyn Maschine, mhandler: &mut MHandler) {
+use grid::MonomeGrid;
+use expression::ExpressionPedal;
+
+fn ev_loop(dev: &mut dyn Maschine, mhandler: &mut MHandler, clock: &SharedClock) {
     let mut fds = [
         PollFd::new(dev.get_fd(), POLLIN, EventFlags::empty()),
         PollFd::new(mhandler.osc_socket.as_raw_fd(), POLLIN, EventFlags::empty())
@@ -78,6 +90,8 @@ fn ev_loop(dev: &mut dyn Maschine, mhandler: &mut MHandler) {
         }
 
         if now.elapsed().unwrap() >= timer_interval {
+            clock.tick();
+            dev.tick(mhandler);
             dev.write_lights();
             now = SystemTime::now();
         }
@@ -85,17 +99,11 @@ fn ev_loop(dev: &mut dyn Maschine, mhandler: &mut MHandler) {
 }
 
 fn usage(prog_name: &String) {
-    println!("usage: {} <hidraw device>", prog_name);
+    println!("usage: {} <hidraw device> [--self-test]", prog_name);
 }
 
 const PAD_RELEASED_BRIGHTNESS: f32 = 0.015;
-
-#[allow(dead_code)]
-enum PressureShape {
-    Linear,
-    Exponential(f32),
-    Constant(f32)
-}
+const MONOME_GRID_PREFIX: &str = "/monome";
 
 struct MHandler<'a> {
     color: HSL,
@@ -107,7 +115,14 @@ struct MHandler<'a> {
     send_aftertouch: bool,
 
     osc_socket: &'a UdpSocket,
-    osc_outgoing_addr: SocketAddr
+    osc_outgoing_addr: SocketAddr,
+
+    // the note each pad is sounding, so that it can always be turned off
+    // again, even if the pad stops playing notes in the meantime.
+    held_notes: [Option<U7>; 16],
+
+    grid: Option<MonomeGrid<'a>>,
+    expression: Option<ExpressionPedal>
 }
 
 fn osc_button_to_btn_map(osc_button: &str) -> Option<MaschineButton> {
@@ -198,11 +213,7 @@ impl<'a> MHandler<'a> {
     }
 
     fn pressure_to_vel(&self, pressure: f32) -> U7 {
-        (match self.pressure_shape {
-            PressureShape::Linear => pressure,
-            PressureShape::Exponential(power) => pressure.powf(power),
-            PressureShape::Constant(c_pressure) => c_pressure
-        } * 127.0) as U7
+        (self.pressure_shape.apply(pressure) * 127.0) as U7
     }
 
     #[allow(dead_code)]
@@ -217,7 +228,99 @@ impl<'a> MHandler<'a> {
         }
     }
 
-    fn recv_osc_msg(&self, maschine: &mut dyn Maschine) {
+    fn send_note_off(&mut self, pad_idx: usize) {
+        if let Some(midi_note) = self.held_notes[pad_idx].take() {
+            self.seq_port.send_message(&Message::NoteOff(Ch1, midi_note, 0)).unwrap();
+        }
+    }
+
+    fn release_held_notes(&mut self) {
+        for pad_idx in 0..16 {
+            self.send_note_off(pad_idx);
+        }
+
+        self.seq_handle.drain_output();
+    }
+
+    fn set_expression_pad(&mut self, pad_idx: usize, cc: U7, hold: bool) {
+        self.send_note_off(pad_idx);
+        self.seq_handle.drain_output();
+
+        self.expression = Some(ExpressionPedal::new(pad_idx, cc, hold));
+    }
+
+    fn is_expression_pad(&self, pad_idx: usize) -> bool {
+        match self.expression {
+            Some(ref expression) => expression.pad_idx() == pad_idx,
+            None => false
+        }
+    }
+
+    // the pad stays lit at the pedal's value, so a held value stays visible.
+    fn update_expression(&mut self, maschine: &mut dyn Maschine, pressure: Option<f32>) {
+        let (pad_idx, cc, value) = match self.expression {
+            Some(ref mut expression) => {
+                let value = match pressure {
+                    Some(pressure) => expression.pressure(pressure),
+                    None => expression.released()
+                };
+
+                (expression.pad_idx(), expression.cc(), value)
+            },
+
+            None => return
+        };
+
+        if let Some(value) = value {
+            self.seq_port.send_message(&Message::ControlChange(Ch1, cc, value)).unwrap();
+            self.seq_handle.drain_output();
+
+            let brightness = ((value as f32) / 127.0).sqrt().max(PAD_RELEASED_BRIGHTNESS);
+            maschine.set_pad_light(pad_idx, self.pad_color(), brightness);
+        }
+    }
+
+    fn set_grid_mode(&mut self, maschine: &mut dyn Maschine, enabled: bool) {
+        // enabling it again keeps the grid's /sys port, host and prefix
+        if enabled == self.grid.is_some() {
+            return;
+        }
+
+        // finish off anything held pads started in the mode being left
+        match self.grid {
+            Some(ref mut grid) => {
+                for pad_idx in 0..16 {
+                    if maschine.get_pad_pressure(pad_idx).unwrap() > 0.0 {
+                        grid.pad_released(maschine, pad_idx);
+                    }
+                }
+            },
+
+            None => self.release_held_notes()
+        }
+
+        // the corners are ordinary keys on a grid, so don't let them blank it
+        maschine.set_reset_gesture_window(if enabled {
+            None
+        } else {
+            Some(Duration::from_millis(DEFAULT_RESET_WINDOW_MS))
+        });
+
+        let brightness = if enabled {
+            self.grid = Some(MonomeGrid::new(self.osc_socket, self.osc_outgoing_addr,
+                                             MONOME_GRID_PREFIX));
+            0.0
+        } else {
+            self.grid = None;
+            PAD_RELEASED_BRIGHTNESS
+        };
+
+        for i in 0..16 {
+            maschine.set_pad_light(i, self.pad_color(), brightness);
+        }
+    }
+
+    fn recv_osc_msg(&mut self, maschine: &mut dyn Maschine) {
         let mut buf = [0u8; 128];
 
         let nbytes = match self.osc_socket.recv_from(&mut buf) {
@@ -239,7 +342,13 @@ impl<'a> MHandler<'a> {
         self.handle_osc_messge(maschine, &msg);
     }
 
-    fn handle_osc_messge(&self, maschine: &mut dyn Maschine, msg: &osc::Message) {
+    fn handle_osc_messge(&mut self, maschine: &mut dyn Maschine, msg: &osc::Message) {
+        if let Some(ref mut grid) = self.grid {
+            if grid.handle_osc_msg(maschine, msg) {
+                return;
+            }
+        }
+
         if msg.path.starts_with("/maschine/button") {
             let btn = match osc_button_to_btn_map(&msg.path[17 ..]) {
                 Some(btn) => btn,
@@ -286,6 +395,40 @@ impl<'a> MHandler<'a> {
                 _ => return
             }
         }
+        else if msg.path.starts_with("/maschine/expression_pad") {
+            match msg.arguments.len() {
+                0 => self.expression = None,
+                3 => {
+                    if let (&osc::Argument::i(pad), &osc::Argument::i(cc), &osc::Argument::i(hold))
+                        = (&msg.arguments[0], &msg.arguments[1], &msg.arguments[2]) {
+                        if pad >= 0 && pad < 16 {
+                            self.set_expression_pad(pad as usize, cc as U7, hold != 0);
+                        }
+                    }
+                }
+                _ => return
+            }
+        }
+        else if msg.path.starts_with("/maschine/grid_mode") {
+            match msg.arguments.len() {
+                1 => {
+                    if let osc::Argument::i(enabled) = msg.arguments[0] {
+                        self.set_grid_mode(maschine, enabled != 0);
+                    }
+                }
+                _ => return
+            }
+        }
+        else if msg.path.starts_with("/maschine/position_mode") {
+            match msg.arguments.len() {
+                1 => {
+                    if let osc::Argument::i(enabled) = msg.arguments[0] {
+                        maschine.set_position_mode(enabled != 0);
+                    }
+                }
+                _ => return
+            }
+        }
 
     }
 
@@ -310,6 +453,10 @@ impl<'a> MHandler<'a> {
     fn send_osc_encoder_msg(&self, delta: i32) {
         self.send_osc_msg("/maschine/encoder", osc_args![delta]);
     }
+
+    fn send_osc_pad_position_msg(&self, pad_idx: usize, position: f32) {
+        self.send_osc_msg("/maschine/pad_position", osc_args![pad_idx as i32, position]);
+    }
 }
 
 const PAD_NOTE_MAP: [U7; 16] = [
@@ -321,16 +468,35 @@ const PAD_NOTE_MAP: [U7; 16] = [
 
 impl<'a> MaschineHandler for MHandler<'a> {
     fn pad_pressed(&mut self, maschine: &mut dyn Maschine, pad_idx: usize, pressure: f32) {
+        if let Some(ref mut grid) = self.grid {
+            return grid.pad_pressed(maschine, pad_idx, pressure);
+        }
+
+        if self.is_expression_pad(pad_idx) {
+            let pressure = maschine.get_pad_pressure(pad_idx).unwrap();
+            return self.update_expression(maschine, Some(pressure));
+        }
+
         let midi_note = maschine.get_midi_note_base() + PAD_NOTE_MAP[pad_idx];
         let msg = Message::NoteOn(Ch1, midi_note, self.pressure_to_vel(pressure));
 
         self.seq_port.send_message(&msg).unwrap();
         self.seq_handle.drain_output();
+        self.held_notes[pad_idx] = Some(midi_note);
 
         maschine.set_pad_light(pad_idx, self.pad_color(), pressure.sqrt());
     }
 
     fn pad_aftertouch(&mut self, maschine: &mut dyn Maschine, pad_idx: usize, pressure: f32) {
+        if self.grid.is_some() {
+            return
+        }
+
+        if self.is_expression_pad(pad_idx) {
+            let pressure = maschine.get_pad_pressure(pad_idx).unwrap();
+            return self.update_expression(maschine, Some(pressure));
+        }
+
         match self.pressure_shape {
             PressureShape::Constant(_) => return,
             _ => {}
@@ -351,14 +517,24 @@ impl<'a> MaschineHandler for MHandler<'a> {
     }
 
     fn pad_released(&mut self, maschine: &mut dyn Maschine, pad_idx: usize) {
-        let midi_note = maschine.get_midi_note_base() + PAD_NOTE_MAP[pad_idx];
-        let msg = Message::NoteOff(Ch1, midi_note, 0);
-        self.seq_port.send_message(&msg).unwrap();
+        if let Some(ref mut grid) = self.grid {
+            return grid.pad_released(maschine, pad_idx);
+        }
+
+        if self.is_expression_pad(pad_idx) {
+            return self.update_expression(maschine, None);
+        }
+
+        self.send_note_off(pad_idx);
         self.seq_handle.drain_output();
 
         maschine.set_pad_light(pad_idx, self.pad_color(), PAD_RELEASED_BRIGHTNESS);
     }
 
+    fn pad_position(&mut self, _: &mut dyn Maschine, pad_idx: usize, position: f32) {
+        self.send_osc_pad_position_msg(pad_idx, position);
+    }
+
     fn encoder_step(&mut self, _: &mut dyn Maschine, _: usize, delta: i32) {
         self.send_osc_encoder_msg(delta);
     }
@@ -370,16 +546,43 @@ impl<'a> MaschineHandler for MHandler<'a> {
     fn button_up(&mut self, _: &mut dyn Maschine, btn: MaschineButton) {
         self.send_osc_button_msg(btn, 0);
     }
+
+    fn reset_gesture(&mut self, maschine: &mut dyn Maschine) {
+        // the grid owns the pad lights and sends no notes
+        if self.grid.is_some() {
+            return;
+        }
+
+        self.release_held_notes();
+
+        // catch anything still sounding that we didn't start, too
+        let msg = Message::ControlChange(Ch1, 123, 0);
+        self.seq_port.send_message(&msg).unwrap();
+        self.seq_handle.drain_output();
+
+        for pad_idx in 0..16 {
+            maschine.set_pad_light(pad_idx, self.pad_color(), PAD_RELEASED_BRIGHTNESS);
+        }
+    }
 }
 
 fn main() {
     let args: Vec<_> = env::args().collect();
 
-    if args.len() != 2 {
+    if args.len() < 2 || args.len() > 3 {
         usage(&args[0]);
         panic!("missing hidraw device path");
     }
 
+    let self_test = match args.get(2).map(|arg| &**arg) {
+        Some("--self-test") => true,
+        Some(_) => {
+            usage(&args[0]);
+            panic!("unknown option {}", args[2]);
+        },
+        None => false
+    };
+
     let dev_fd = match fcntl::open(Path::new(&args[1]), O_RDWR | O_NONBLOCK,
                                    sys::stat::Mode::empty()) {
         Err(err) => panic!("couldn't open {}: {}", args[1],
@@ -396,6 +599,16 @@ fn main() {
 
     let mut dev = devices::mk2::Mikro::new(dev_fd);
 
+    if self_test {
+        let report = dev.self_test();
+
+        println!(" :: self-test {}: {:?}",
+                 if report.passed() { "passed" } else { "FAILED" }, report);
+    }
+
+    let clock = SharedClock::new();
+    dev.set_clock(clock.clone());
+
     let mut handler = MHandler {
         color: HSL {
             h: 0.0,
@@ -411,7 +624,12 @@ fn main() {
 
         osc_socket: &osc_socket,
         osc_outgoing_addr: SocketAddr::V4(
-            SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 42435))
+            SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 42435)),
+
+        held_notes: [None; 16],
+
+        grid: None,
+        expression: None
     };
 
     dev.clear_screen();
@@ -420,5 +638,5 @@ fn main() {
         dev.set_pad_light(i, handler.pad_color(), PAD_RELEASED_BRIGHTNESS);
     }
 
-    ev_loop(&mut dev, &mut handler);
+    ev_loop(&mut dev, &mut handler, &clock);
 }
@@ -0,0 +1,694 @@
+//  maschine.rs: user-space drivers for native instruments USB HIDs
+//  Copyright (C) 2015 William Light <wrl@illest.net>
+//
+//  This program is free software: you can redistribute it and/or modify
+//  it under the terms of the GNU Lesser General Public License as
+//  published by the Free Software Foundation, either version 3 of the
+//  License, or (at your option) any later version.
+//
+//  This program is distributed in the hope that it will be useful,
+//  but WITHOUT ANY WARRANTY; without even the implied warranty of
+//  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+//  GNU Lesser General Public License for more details.
+//
+//  You should have received a copy of the GNU Lesser General Public
+//  License along with this program.  If not, see
+//  <http://www.gnu.org/licenses/>.
+
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::net::UdpSocket;
+use std::os::unix::io::IntoRawFd;
+use std::os::unix::net::UnixDatagram;
+use std::time::{
+    Duration,
+    Instant
+};
+
+use super::*;
+use midi::U7;
+use base::{
+    Direction,
+    PadStrike,
+    SharedClock,
+    PressureShape,
+    Rect
+};
+use devices::mk2::Mikro;
+use grid::MonomeGrid;
+use expression::ExpressionPedal;
+
+// a socketpair stands in for the hidraw node: reports written to one end are
+// read by the device, and anything the device writes can be read back.
+fn mock_mikro() -> (Mikro, UnixDatagram) {
+    let (dev_end, test_end) = UnixDatagram::pair().unwrap();
+    (Mikro::new(dev_end.into_raw_fd()), test_end)
+}
+
+fn send_pads(dev: &mut Mikro, sock: &UnixDatagram, handler: &mut dyn MaschineHandler,
+             pressures: &[f32; 16]) {
+    let mut report = [0u8; 33];
+    report[0] = 0x20;
+
+    for (i, &pressure) in pressures.iter().enumerate() {
+        let val = (pressure * 4095.0) as u16;
+        report[1 + (i * 2)] = (val & 0xFF) as u8;
+        report[2 + (i * 2)] = (val >> 8) as u8;
+    }
+
+    sock.send(&report).unwrap();
+    dev.readable(handler);
+}
+
+// enough reports to push the pad's median filter over (or back under) the
+// press threshold.
+const FILTER_SETTLE_REPORTS: usize = 8;
+
+fn hold_pads(dev: &mut Mikro, sock: &UnixDatagram, handler: &mut dyn MaschineHandler,
+             pressures: &[f32; 16]) {
+    for _ in 0..FILTER_SETTLE_REPORTS {
+        send_pads(dev, sock, handler, pressures);
+    }
+}
+
+fn release_pads(dev: &mut Mikro, sock: &UnixDatagram, handler: &mut dyn MaschineHandler) {
+    hold_pads(dev, sock, handler, &[0.0; 16]);
+}
+
+fn strike_pad(dev: &mut Mikro, sock: &UnixDatagram, handler: &mut dyn MaschineHandler,
+              pad_idx: usize, pressure: f32) {
+    let mut pressures = [0.0; 16];
+    pressures[pad_idx] = pressure;
+
+    hold_pads(dev, sock, handler, &pressures);
+    release_pads(dev, sock, handler);
+}
+
+fn send_buttons(dev: &mut Mikro, sock: &UnixDatagram, handler: &mut dyn MaschineHandler,
+                buttons: [u8; 4]) {
+    let report = [0x01, buttons[0], buttons[1], buttons[2], buttons[3], 0];
+
+    sock.send(&report).unwrap();
+    dev.readable(handler);
+}
+
+// bits within the third byte of the button report
+const NAV_LEFT: u8 = 0x04;
+const NAV_RIGHT: u8 = 0x02;
+
+// reassembles the four screen reports written by the device into a
+// pixel lookup.
+fn read_screen(sock: &UnixDatagram) -> Vec<Vec<u8>> {
+    (0..4).map(|_| {
+        let mut buf = vec![0u8; 512];
+        let nbytes = sock.recv(&mut buf).unwrap();
+        assert_eq!(buf[0], 0xE0);
+        buf.truncate(nbytes);
+        buf
+    }).collect()
+}
+
+fn screen_pixel(chunks: &[Vec<u8>], x: usize, y: usize) -> bool {
+    let chunk = &chunks[x / 32];
+    assert_eq!(chunk[1] as usize, (x / 32) * 32);
+
+    (chunk[9 + ((y / 8) * 32) + (x % 32)] & (1 << (y % 8))) != 0
+}
+
+fn read_lights(dev: &mut Mikro, sock: &UnixDatagram) -> Vec<u8> {
+    dev.write_lights();
+
+    let mut buf = vec![0u8; 128];
+    let nbytes = sock.recv(&mut buf).unwrap();
+    assert_eq!(buf[0], 0x80);
+    buf.truncate(nbytes);
+    buf
+}
+
+#[derive(Default)]
+struct RecordingHandler {
+    pressed: Vec<(usize, f32)>,
+    released: Vec<usize>,
+    positions: Vec<(usize, f32)>,
+    directions: Vec<Direction>,
+    resets: usize
+}
+
+impl MaschineHandler for RecordingHandler {
+    fn pad_pressed(&mut self, _: &mut dyn Maschine, pad_idx: usize, pressure: f32) {
+        self.pressed.push((pad_idx, pressure));
+    }
+
+    fn pad_released(&mut self, _: &mut dyn Maschine, pad_idx: usize) {
+        self.released.push(pad_idx);
+    }
+
+    fn pad_position(&mut self, _: &mut dyn Maschine, pad_idx: usize, position: f32) {
+        self.positions.push((pad_idx, position));
+    }
+
+    fn direction(&mut self, _: &mut dyn Maschine, direction: Direction) {
+        self.directions.push(direction);
+    }
+
+    fn reset_gesture(&mut self, _: &mut dyn Maschine) {
+        self.resets += 1;
+    }
+}
+
+#[test]
+fn test_audio_hook_on_pad_press() {
+    let (mut dev, sock) = mock_mikro();
+    let mut handler = RecordingHandler::default();
+
+    let strikes = Rc::new(RefCell::new(Vec::<PadStrike>::new()));
+    let hook_strikes = strikes.clone();
+    dev.set_audio_hook(Box::new(move |strike| hook_strikes.borrow_mut().push(strike)));
+
+    let before = Instant::now();
+    strike_pad(&mut dev, &sock, &mut handler, 5, 0.5);
+    let after = Instant::now();
+
+    let strikes = strikes.borrow();
+    assert_eq!(strikes.len(), 1);
+    assert_eq!(strikes[0].pad_idx, 5);
+    assert_eq!(strikes[0].velocity, handler.pressed[0].1);
+    assert!((strikes[0].velocity - 0.5).abs() < 0.001);
+    assert!(strikes[0].timestamp >= before && strikes[0].timestamp <= after);
+
+    assert_eq!(handler.pressed.len(), 1);
+    assert_eq!(handler.released, vec![5]);
+}
+
+#[test]
+fn test_draw_linear_velocity_curve() {
+    let (mut dev, sock) = mock_mikro();
+    let mut handler = RecordingHandler::default();
+    let rect = Rect { x: 10, y: 8, width: 48, height: 48 };
+
+    let on_diagonal = |x: usize, y: usize| {
+        x >= rect.x && x < rect.x + rect.width
+            && y == rect.y + (rect.height - 1) - (x - rect.x)
+    };
+
+    dev.draw_velocity_curve(rect, &PressureShape::Linear, true);
+    let chunks = read_screen(&sock);
+
+    for y in 0..64 {
+        for x in 0..128 {
+            assert_eq!(screen_pixel(&chunks, x, y), on_diagonal(x, y),
+                       "pixel ({}, {})", x, y);
+        }
+    }
+
+    strike_pad(&mut dev, &sock, &mut handler, 0, 0.5);
+
+    // the last hit is only marked when asked for
+    for &show_last_hit in [false, true].iter() {
+        dev.draw_velocity_curve(rect, &PressureShape::Linear, show_last_hit);
+        let chunks = read_screen(&sock);
+
+        let marked = (0..64)
+            .flat_map(|y| (0..128).map(move |x| (x, y)))
+            .any(|(x, y)| screen_pixel(&chunks, x, y) && !on_diagonal(x, y));
+
+        assert_eq!(marked, show_last_hit);
+    }
+}
+
+#[test]
+fn test_auto_velocity_normalize_tames_hot_pad() {
+    let (mut dev, sock) = mock_mikro();
+    let mut handler = RecordingHandler::default();
+
+    dev.set_auto_velocity_normalize(true);
+
+    for _ in 0..30 {
+        for pad in 1..4 {
+            strike_pad(&mut dev, &sock, &mut handler, pad, 0.5);
+        }
+
+        strike_pad(&mut dev, &sock, &mut handler, 0, 0.9);
+    }
+
+    let hot: Vec<f32> = handler.pressed.iter()
+        .filter(|&&(pad, _)| pad == 0)
+        .map(|&(_, velocity)| velocity)
+        .collect();
+
+    let (_, cold) = *handler.pressed.iter()
+        .rev()
+        .find(|&&(pad, _)| pad == 1)
+        .unwrap();
+
+    assert!(hot[0] > 0.8);
+    assert!(hot.windows(2).all(|pair| pair[1] <= pair[0]));
+    assert!((hot[hot.len() - 1] - cold).abs() < 0.02);
+
+    assert!(dev.get_velocity_gains()[0] < 1.0);
+
+    // once frozen, the learned gains stay put
+    let gains = dev.get_velocity_gains();
+    dev.freeze_velocity_normalize(true);
+    strike_pad(&mut dev, &sock, &mut handler, 0, 0.9);
+    assert_eq!(dev.get_velocity_gains(), gains);
+}
+
+#[test]
+fn test_corner_pads_reset_gesture() {
+    let (mut dev, sock) = mock_mikro();
+    let mut handler = RecordingHandler::default();
+
+    dev.set_pad_light(5, 0xFFFFFF, 1.0);
+    dev.set_button_light(MaschineButton::Play, 0xFFFFFF, 1.0);
+
+    // three corners isn't enough
+    let mut pressures = [0.0; 16];
+    pressures[0] = 0.5;
+    pressures[3] = 0.5;
+    pressures[12] = 0.5;
+
+    hold_pads(&mut dev, &sock, &mut handler, &pressures);
+    release_pads(&mut dev, &sock, &mut handler);
+    assert_eq!(handler.resets, 0);
+
+    pressures[15] = 0.5;
+    hold_pads(&mut dev, &sock, &mut handler, &pressures);
+    assert_eq!(handler.resets, 1);
+
+    let lights = read_lights(&mut dev, &sock);
+    assert!(lights[1 ..].iter().all(|&byte| byte == 0));
+
+    // holding the corners down doesn't retrigger it
+    hold_pads(&mut dev, &sock, &mut handler, &pressures);
+    release_pads(&mut dev, &sock, &mut handler);
+    assert_eq!(handler.resets, 1);
+
+    dev.set_reset_gesture_window(None);
+    hold_pads(&mut dev, &sock, &mut handler, &pressures);
+    assert_eq!(handler.resets, 1);
+}
+
+#[test]
+fn test_velocity_compression() {
+    let (mut dev, sock) = mock_mikro();
+    let mut handler = RecordingHandler::default();
+
+    dev.set_velocity_compression(0.5, 4.0);
+
+    strike_pad(&mut dev, &sock, &mut handler, 0, 0.3);
+    strike_pad(&mut dev, &sock, &mut handler, 1, 0.5);
+    strike_pad(&mut dev, &sock, &mut handler, 2, 0.9);
+
+    let velocities: Vec<f32> = handler.pressed.iter().map(|&(_, v)| v).collect();
+
+    assert!((velocities[0] - 0.3).abs() < 0.001);
+    assert!((velocities[1] - 0.5).abs() < 0.001);
+    assert!((velocities[2] - (0.5 + (0.4 / 4.0))).abs() < 0.001);
+}
+
+#[test]
+fn test_shared_clock_aligns_metronomes() {
+    let (mut dev_a, sock_a) = mock_mikro();
+    let (mut dev_b, sock_b) = mock_mikro();
+
+    let clock = SharedClock::new();
+
+    dev_a.set_clock(clock.clone());
+    dev_b.set_clock(clock.clone());
+
+    dev_a.set_metronome(Some(4));
+    dev_b.set_metronome(Some(4));
+
+    let mut flashes = 0;
+
+    for _ in 0..12 {
+        clock.tick();
+
+        // play button brightness
+        let a = read_lights(&mut dev_a, &sock_a)[19];
+        let b = read_lights(&mut dev_b, &sock_b)[19];
+
+        assert_eq!(a, b);
+
+        if a > 0 {
+            flashes += 1;
+        }
+    }
+
+    assert_eq!(flashes, 3);
+}
+
+#[test]
+fn test_zero_ticks_per_beat_turns_metronome_off() {
+    let (mut dev, sock) = mock_mikro();
+
+    dev.set_metronome(Some(4));
+    assert!(read_lights(&mut dev, &sock)[19] > 0);
+
+    dev.set_metronome(Some(0));
+    assert_eq!(read_lights(&mut dev, &sock)[19], 0);
+}
+
+#[test]
+fn test_metronome_leaves_play_light_between_beats() {
+    let (mut dev, sock) = mock_mikro();
+
+    let clock = SharedClock::new();
+    dev.set_clock(clock.clone());
+    dev.set_metronome(Some(4));
+
+    clock.tick();
+    assert_eq!(read_lights(&mut dev, &sock)[19], 0);
+
+    // lit from elsewhere (say, over OSC) partway through the beat
+    dev.set_button_light(MaschineButton::Play, 0xFFFFFF, 1.0);
+    clock.tick();
+    assert_eq!(read_lights(&mut dev, &sock)[19], 255);
+
+    clock.tick();
+    assert_eq!(read_lights(&mut dev, &sock)[19], 255);
+
+    // and the next beat's flash takes it over again
+    clock.tick();
+    assert_eq!(read_lights(&mut dev, &sock)[19], 255);
+
+    clock.tick();
+    assert_eq!(read_lights(&mut dev, &sock)[19], 0);
+}
+
+#[test]
+fn test_pad_position_follows_pressure() {
+    let (mut dev, sock) = mock_mikro();
+    let mut handler = RecordingHandler::default();
+
+    let mut pressures = [0.0; 16];
+    pressures[9] = 0.2;
+
+    hold_pads(&mut dev, &sock, &mut handler, &pressures);
+    release_pads(&mut dev, &sock, &mut handler);
+    assert!(handler.positions.is_empty());
+
+    dev.set_position_mode(true);
+    dev.set_position_range(0.25, 0.75, false);
+
+    hold_pads(&mut dev, &sock, &mut handler, &pressures);
+
+    for &pressure in [0.4, 0.6, 0.8].iter() {
+        pressures[9] = pressure;
+        hold_pads(&mut dev, &sock, &mut handler, &pressures);
+    }
+
+    let positions: Vec<f32> = handler.positions.iter()
+        .map(|&(pad, position)| { assert_eq!(pad, 9); position })
+        .collect();
+
+    // one message per level, however many reports it was held for
+    assert_eq!(positions.len(), 4);
+    assert!(positions.windows(2).all(|pair| pair[1] > pair[0]));
+
+    for (&position, &pressure) in positions.iter().zip([0.2, 0.4, 0.6, 0.8].iter()) {
+        assert!((position - (0.25 + 0.5 * pressure)).abs() < 0.001);
+    }
+}
+
+#[test]
+fn test_nav_buttons_as_direction_pad() {
+    let (mut dev, sock) = mock_mikro();
+    let mut handler = RecordingHandler::default();
+
+    let clock = SharedClock::new();
+    dev.set_clock(clock.clone());
+    dev.set_autorepeat(3, 2);
+
+    let tick = |dev: &mut Mikro, handler: &mut RecordingHandler, ticks: usize| {
+        for _ in 0..ticks {
+            clock.tick();
+            dev.tick(handler);
+        }
+    };
+
+    send_buttons(&mut dev, &sock, &mut handler, [0, 0, NAV_LEFT, 0]);
+    assert_eq!(handler.directions, vec![Direction::Left]);
+
+    // nothing until the delay has passed, then one every interval
+    tick(&mut dev, &mut handler, 2);
+    assert_eq!(handler.directions.len(), 1);
+    tick(&mut dev, &mut handler, 1);
+    assert_eq!(handler.directions.len(), 2);
+    tick(&mut dev, &mut handler, 4);
+    assert_eq!(handler.directions, vec![Direction::Left; 4]);
+
+    send_buttons(&mut dev, &sock, &mut handler, [0, 0, 0, 0]);
+    tick(&mut dev, &mut handler, 10);
+    assert_eq!(handler.directions.len(), 4);
+
+    handler.directions.clear();
+
+    send_buttons(&mut dev, &sock, &mut handler, [0, 0, NAV_RIGHT, 0]);
+    tick(&mut dev, &mut handler, 3);
+    send_buttons(&mut dev, &sock, &mut handler, [0, 0, 0, 0]);
+    tick(&mut dev, &mut handler, 10);
+    assert_eq!(handler.directions, vec![Direction::Right; 2]);
+
+    // disabling the d-pad while a direction is held stops the repeats
+    handler.directions.clear();
+
+    send_buttons(&mut dev, &sock, &mut handler, [0, 0, NAV_LEFT, 0]);
+    dev.set_direction_pad(false);
+    tick(&mut dev, &mut handler, 10);
+    assert_eq!(handler.directions, vec![Direction::Left]);
+
+    dev.set_direction_pad(true);
+    tick(&mut dev, &mut handler, 10);
+    assert_eq!(handler.directions, vec![Direction::Left]);
+}
+
+#[test]
+fn test_monome_grid_key_and_led() {
+    let (mut dev, sock) = mock_mikro();
+
+    let grid_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let app_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let mut grid = MonomeGrid::new(&grid_socket, app_socket.local_addr().unwrap(), "/monome");
+
+    let mut pressures = [0.0; 16];
+    pressures[9] = 0.5;
+    hold_pads(&mut dev, &sock, &mut grid, &pressures);
+
+    let mut buf = [0u8; 128];
+    let nbytes = app_socket.recv(&mut buf).unwrap();
+    let msg = osc::Message::deserialize(&buf[.. nbytes]).unwrap();
+
+    assert_eq!(msg.path, "/monome/grid/key");
+    match (&msg.arguments[0], &msg.arguments[1], &msg.arguments[2]) {
+        (&osc::Argument::i(x), &osc::Argument::i(y), &osc::Argument::i(s)) =>
+            assert_eq!((x, y, s), (1, 2, 1)),
+        _ => panic!("unexpected /grid/key arguments")
+    }
+
+    let msg = osc::Message {
+        path: "/monome/grid/led/set",
+        arguments: osc_args![2, 3, 1]
+    };
+
+    assert!(grid.handle_osc_msg(&mut dev, &msg));
+
+    // pad (2, 3) is pad 14, whose rgb triple starts at 31 + (14 * 3)
+    let lights = read_lights(&mut dev, &sock);
+    for pad_idx in 0..16 {
+        let lit = lights[31 + (pad_idx * 3) .. 31 + ((pad_idx + 1) * 3)]
+            .iter().any(|&byte| byte > 0);
+
+        assert_eq!(lit, pad_idx == 14);
+    }
+}
+
+#[test]
+fn test_monome_grid_sys_messages() {
+    let (mut dev, sock) = mock_mikro();
+
+    let grid_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let maschine_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let app_socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let mut grid = MonomeGrid::new(&grid_socket, maschine_socket.local_addr().unwrap(), "/monome");
+
+    let app_port = app_socket.local_addr().unwrap().port() as i32;
+    let mut buf = [0u8; 128];
+
+    for msg in [
+        osc::Message { path: "/sys/port", arguments: osc_args![app_port] },
+
+        // out of range, so these leave the port alone rather than wrapping
+        osc::Message { path: "/sys/port", arguments: osc_args![-1] },
+        osc::Message { path: "/sys/port", arguments: osc_args![app_port + 65536] },
+
+        osc::Message { path: "/sys/prefix", arguments: vec![osc::Argument::s("/app")] },
+        osc::Message { path: "/sys/size", arguments: vec![] }
+    ].iter() {
+        assert!(grid.handle_osc_msg(&mut dev, msg));
+    }
+
+    let nbytes = app_socket.recv(&mut buf).unwrap();
+    let msg = osc::Message::deserialize(&buf[.. nbytes]).unwrap();
+
+    assert_eq!(msg.path, "/sys/size");
+    match (&msg.arguments[0], &msg.arguments[1]) {
+        (&osc::Argument::i(w), &osc::Argument::i(h)) => assert_eq!((w, h), (4, 4)),
+        _ => panic!("unexpected /sys/size arguments")
+    }
+
+    let mut pressures = [0.0; 16];
+    pressures[0] = 0.5;
+    hold_pads(&mut dev, &sock, &mut grid, &pressures);
+
+    let nbytes = app_socket.recv(&mut buf).unwrap();
+    let msg = osc::Message::deserialize(&buf[.. nbytes]).unwrap();
+    assert_eq!(msg.path, "/app/grid/key");
+
+    // led messages only answer to the new prefix
+    let msg = osc::Message {
+        path: "/monome/grid/led/all",
+        arguments: osc_args![1]
+    };
+
+    assert!(!grid.handle_osc_msg(&mut dev, &msg));
+}
+
+#[test]
+fn test_expression_pedal_hold() {
+    for &hold in [true, false].iter() {
+        let mut pedal = ExpressionPedal::new(3, 11, hold);
+        let mut sent = Vec::new();
+
+        for _ in 0..30 {
+            sent.extend(pedal.pressure(0.6));
+        }
+
+        // smoothed, so it creeps up to the pressure rather than jumping
+        assert!(sent.len() > 1);
+        assert!(sent.windows(2).all(|pair| pair[1] > pair[0]));
+        assert_eq!(pedal.value(), 76);
+
+        let released = pedal.released();
+
+        if hold {
+            assert_eq!(released, None);
+            assert_eq!(pedal.value(), 76);
+        } else {
+            assert_eq!(released, Some(0));
+            assert_eq!(pedal.value(), 0);
+        }
+    }
+}
+
+// feeds the pedal the way MHandler does, from the device's filtered pressure.
+struct ExpressionHandler {
+    pedal: ExpressionPedal,
+    sent: Vec<U7>
+}
+
+impl MaschineHandler for ExpressionHandler {
+    fn pad_pressed(&mut self, maschine: &mut dyn Maschine, pad_idx: usize, _: f32) {
+        let pressure = maschine.get_pad_pressure(pad_idx).unwrap();
+        self.sent.extend(self.pedal.pressure(pressure));
+    }
+
+    fn pad_aftertouch(&mut self, maschine: &mut dyn Maschine, pad_idx: usize, _: f32) {
+        let pressure = maschine.get_pad_pressure(pad_idx).unwrap();
+        self.sent.extend(self.pedal.pressure(pressure));
+    }
+
+    fn pad_released(&mut self, _: &mut dyn Maschine, _: usize) {
+        self.sent.extend(self.pedal.released());
+    }
+}
+
+#[test]
+fn test_expression_pedal_hold_through_gradual_release() {
+    for &hold in [true, false].iter() {
+        let (mut dev, sock) = mock_mikro();
+        let mut handler = ExpressionHandler {
+            pedal: ExpressionPedal::new(3, 11, hold),
+            sent: Vec::new()
+        };
+
+        let mut pressures = [0.0; 16];
+        pressures[3] = 0.6;
+
+        for _ in 0..30 {
+            send_pads(&mut dev, &sock, &mut handler, &pressures);
+        }
+
+        assert_eq!(handler.sent.last(), Some(&76));
+
+        // easing off while the pad is still held is followed down
+        pressures[3] = 0.3;
+
+        for _ in 0..30 {
+            send_pads(&mut dev, &sock, &mut handler, &pressures);
+        }
+
+        assert_eq!(handler.sent.last(), Some(&38));
+
+        // lift off over 10 reports, then let go entirely
+        for step in 1..11 {
+            pressures[3] = 0.3 * (1.0 - ((step as f32) / 10.0));
+            send_pads(&mut dev, &sock, &mut handler, &pressures);
+        }
+
+        release_pads(&mut dev, &sock, &mut handler);
+        assert_eq!(dev.get_pad_pressure(3), Ok(0.0));
+
+        if hold {
+            assert_eq!(handler.sent.last(), Some(&38));
+            assert_eq!(handler.pedal.value(), 38);
+        } else {
+            assert_eq!(handler.sent.last(), Some(&0));
+        }
+    }
+}
+
+#[test]
+fn test_self_test() {
+    let (mut dev, sock) = mock_mikro();
+
+    dev.set_pad_light(0, 0xFF0000, 1.0);
+    let before = read_lights(&mut dev, &sock);
+
+    for _ in 0..2 {
+        sock.send(&[0x20; 33]).unwrap();
+    }
+
+    let started = Instant::now();
+    let report = dev.self_test();
+
+    // on for long enough to actually be seen
+    assert!(started.elapsed() >= Duration::from_millis(300));
+
+    let mut buf = vec![0u8; 128];
+    // every light at full brightness, with the rgb ones (the group button
+    // and the pads) at the halved level set_rgb_light gives them
+    let nbytes = sock.recv(&mut buf).unwrap();
+    assert_eq!(buf[0], 0x80);
+    assert_eq!(nbytes, 79);
+
+    for (idx, &byte) in buf.iter().enumerate().take(nbytes).skip(1) {
+        match idx {
+            9 ..= 11 | 31 ..= 78 => assert_eq!(byte, 127, "light {}", idx),
+            _ => assert_eq!(byte, 255, "light {}", idx)
+        }
+    }
+
+    let chunks = read_screen(&sock);
+    assert!(chunks.iter().all(|chunk| chunk[9 ..].iter().all(|&byte| byte == 0)));
+
+    let nbytes = sock.recv(&mut buf).unwrap();
+    assert_eq!(&buf[.. nbytes], &before[..]);
+
+    assert!(report.lights_written);
+    assert!(report.screen_cleared);
+    assert_eq!(report.reports_read, 2);
+    assert!(report.passed());
+}